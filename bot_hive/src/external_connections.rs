@@ -1,2 +1,3 @@
 pub mod common;
 pub mod discord;
+pub mod matrix;
@@ -1 +1,138 @@
+use regex::Regex;
 
+///Strips the bot out of the raw message text, returning the cleaned text and whether the bot was addressed.
+/// - trims leading and trailing spaces ("   /hellow    @machinelifeformbot   world  " becomes "/hellow    @machinelifeformbot   world").
+/// - removes / from start if it's there ("/hellow    @machinelifeformbot   world" becomes "hellow    @machinelifeformbot   world").
+/// - removes mentions of the bot from the message ("hellow    @machinelifeformbot   world" becomes "hellow      world").
+/// - replaces redundant spaces with single spaces using regex ("hellow      world" becomes "hellow world").
+/// - removes the wake word from the start if one is given ("bot hellow world" becomes "hellow world").
+pub fn clean_message(content: &str, bot_handle: &str, wake_word: Option<&str>) -> (String, bool) {
+    //-----------------------remove self mention from message
+    let msg = content
+        .replace(bot_handle, "")
+        .trim()
+        .trim_start_matches('/')
+        .trim()
+        .to_lowercase();
+
+    let space_trimmer = Regex::new(r"\s+").unwrap();
+
+    let msg: String = space_trimmer.replace_all(&msg, " ").into();
+
+    let mentioned = content.contains(bot_handle);
+
+    let woken = wake_word
+        .map(|wake_word| wake_word.to_lowercase())
+        .and_then(|wake_word| match msg.strip_prefix(wake_word.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with(' ') => Some(rest.trim().to_owned()),
+            _ => None,
+        });
+
+    match woken {
+        Some(msg) => (msg, true),
+        None => (msg, mentioned),
+    }
+}
+
+///Private messages start a conversation on their own unless a wake word is required, anything else has to address the bot.
+pub fn starts_conversation(addressed: bool, is_private: bool, wake_word: Option<&str>) -> bool {
+    addressed || (is_private && wake_word.is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLE: &str = "<@1234>";
+
+    #[test]
+    fn trims_leading_slash() {
+        assert_eq!(
+            clean_message("  /hellow world  ", HANDLE, None),
+            ("hellow world".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn removes_mention() {
+        assert_eq!(
+            clean_message("<@1234> hellow", HANDLE, None),
+            ("hellow".to_owned(), true)
+        );
+        assert_eq!(
+            clean_message("/hellow <@1234> world", HANDLE, None),
+            ("hellow world".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn collapses_spaces_and_lowercases() {
+        assert_eq!(
+            clean_message("Hellow    \t  WORLD", HANDLE, None),
+            ("hellow world".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn mention_only_message_is_empty() {
+        assert_eq!(
+            clean_message("<@1234>", HANDLE, None),
+            ("".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn strips_wake_word() {
+        assert_eq!(
+            clean_message("Bot hellow world", HANDLE, Some("bot")),
+            ("hellow world".to_owned(), true)
+        );
+        assert_eq!(
+            clean_message("bot", HANDLE, Some("bot")),
+            ("".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn wake_word_must_be_a_whole_leading_word() {
+        assert_eq!(
+            clean_message("bottle of water", HANDLE, Some("bot")),
+            ("bottle of water".to_owned(), false)
+        );
+        assert_eq!(
+            clean_message("hey bot", HANDLE, Some("bot")),
+            ("hey bot".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn private_messages_start_conversations() {
+        assert!(starts_conversation(false, true, None));
+        assert!(starts_conversation(true, true, None));
+    }
+
+    #[test]
+    fn other_messages_need_a_mention() {
+        assert!(!starts_conversation(false, false, None));
+        assert!(starts_conversation(true, false, None));
+    }
+
+    #[test]
+    fn wake_word_is_required_everywhere() {
+        assert!(!starts_conversation(false, true, Some("bot")));
+        assert!(!starts_conversation(false, false, Some("bot")));
+        assert!(starts_conversation(true, true, Some("bot")));
+        assert!(starts_conversation(true, false, Some("bot")));
+    }
+
+    #[test]
+    fn private_message_without_wake_word_is_not_addressed() {
+        let (msg, addressed) = clean_message("hellow", HANDLE, Some("bot"));
+        assert!(!starts_conversation(addressed, true, Some("bot")));
+        assert_eq!(msg, "hellow");
+
+        let (msg, addressed) = clean_message("bot hellow", HANDLE, Some("bot"));
+        assert!(starts_conversation(addressed, true, Some("bot")));
+        assert_eq!(msg, "hellow");
+    }
+}
@@ -1,8 +1,21 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
 use lib_hive::LifeCycleHandle;
-use regex::Regex;
-use serenity::{async_trait, model::channel::Message as DMessage, prelude::*};
+use serenity::{
+    all::{ActivityData, Ready},
+    async_trait,
+    model::channel::Message as DMessage,
+    prelude::*,
+};
 
-use crate::models::user::{User, UserAction, UserChannel, UserId};
+use crate::{
+    configuration,
+    external_connections::common::{clean_message, starts_conversation},
+    models::user::{User, UserAction, UserChannel, UserId},
+};
 
 pub async fn prepare_discord_client(
     discord_token: &str,
@@ -14,7 +27,11 @@ pub async fn prepare_discord_client(
 
     // Create a new instance of the Client, logging in as a bot. This will
     let client = Client::builder(discord_token, intents)
-        .event_handler(Handler { user_life_cycle })
+        .activity(ActivityData::custom(configuration::DISCORD_STARTUP_STATUS))
+        .event_handler(Handler {
+            user_life_cycle,
+            refreshing_status: AtomicBool::new(false),
+        })
         .await?;
 
     Ok(client)
@@ -28,55 +45,136 @@ pub async fn run_discord(mut client: Client) -> anyhow::Result<()> {
 
 struct Handler {
     user_life_cycle: LifeCycleHandle<UserId, UserAction>,
+    refreshing_status: AtomicBool,
+}
+
+impl Handler {
+    async fn dispatch(&self, user_id: UserId, action: UserAction) {
+        if let Err(err) = self.user_life_cycle.act(user_id, action).await {
+            println!("Failed to dispatch discord event: {err}");
+        }
+    }
 }
 
 #[async_trait]
 impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        let user_life_cycle = self.user_life_cycle.clone();
+        let set_status = move || {
+            let status = build_status(
+                configuration::DISCORD_STATUS,
+                user_life_cycle.entity_count(),
+            );
+            ctx.set_activity(Some(ActivityData::custom(status)));
+        };
+
+        match configuration::DISCORD_STATUS_REFRESH_SECONDS {
+            Some(0) => {
+                println!("DISCORD_STATUS_REFRESH_SECONDS must be above 0, setting status once");
+                set_status();
+            }
+            // Ready fires again after a reconnect, only one refresher is needed.
+            Some(_) if self.refreshing_status.swap(true, Ordering::Relaxed) => {}
+            Some(seconds) => {
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(Duration::from_secs(seconds));
+                    loop {
+                        interval.tick().await;
+                        set_status();
+                    }
+                });
+            }
+            None => set_status(),
+        }
+    }
+
     // Set a handler for the `message` event - so that whenever a new message
     // is received - the closure (or function) passed will be called.
     async fn message(&self, ctx: Context, message: DMessage) {
         if !message.author.bot {
-            if let Some((msg, start_conversation)) = filter(&message, &ctx).await {
+            if let Some(handle) = bot_handle(&ctx).await {
+                let (msg, start_conversation) = filter(&message, &handle);
                 let user_id = UserId(UserChannel::Discord, message.author.id.get().to_string());
+                let display_name =
+                    display_name(&message.author, configuration::INCLUDE_DISPLAY_NAME);
                 let action = UserAction::NewMessage {
                     start_conversation,
                     msg,
+                    display_name,
                 };
-                self.user_life_cycle.act(user_id, action).await;
+                self.dispatch(user_id, action).await;
             }
         }
     }
 }
 
-///Filter basically does some spring cleaning.
-/// - checks whether the update is actually a message or some other type.
-/// - trims leading and trailing spaces ("   /hellow    @machinelifeformbot   world  " becomes "/hellow    @machinelifeformbot   world").
-/// - removes / from start if it's there ("/hellow    @machinelifeformbot   world" becomes "hellow    @machinelifeformbot   world").
-/// - removes mentions of the bot from the message ("hellow    @machinelifeformbot   world" becomes "hellow      world").
-/// - replaces redundant spaces with single spaces using regex ("hellow      world" becomes "hellow world").
-async fn filter(message: &DMessage, ctx: &Context) -> Option<(String, bool)> {
+///Fills the status template, `{count}` becomes the number of conversations seen since startup.
+fn build_status(template: &str, count: usize) -> String {
+    template.replace("{count}", &count.to_string())
+}
+
+///The name users see for the author, their global name if they set one.
+fn display_name(author: &serenity::all::User, include: bool) -> Option<String> {
+    match include {
+        true => Some(
+            author
+                .global_name
+                .clone()
+                .unwrap_or_else(|| author.name.clone()),
+        ),
+        false => None,
+    }
+}
+
+///The bot's mention as it appears in message content, e.g. "<@1234>".
+async fn bot_handle(ctx: &Context) -> Option<String> {
     let Ok(info) = ctx.http.get_current_application_info().await else {
         return None;
     };
 
     let id: i64 = info.id.into();
-    //-----------------------remove self mention from message
-    let handle = format!("<@{}>", &id);
-
-    let msg = message
-        .content
-        .replace(handle.as_str(), "")
-        .trim()
-        .trim_start_matches('/')
-        .trim()
-        .to_lowercase();
-
-    let space_trimmer = Regex::new(r"\s+").unwrap();
+    Some(format!("<@{}>", &id))
+}
 
-    let msg: String = space_trimmer.replace_all(&msg, " ").into();
+///Filter basically does some spring cleaning.
+/// - cleans up the text via `clean_message`.
+/// - starts a conversation if the message mentions the bot or starts with the wake word.
+/// - private messages always start a conversation unless a wake word is required.
+fn filter(message: &DMessage, handle: &str) -> (String, bool) {
+    let wake_word = configuration::REQUIRE_WAKE_WORD;
+    let (msg, addressed) = clean_message(&message.content, handle, wake_word);
     //-----------------------check if message is from a group chat.......
-    Some((
+    (
         msg,
-        message.is_private() || message.content.contains(handle.as_str()),
-    ))
+        starts_conversation(addressed, message.is_private(), wake_word),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_status_fills_count() {
+        assert_eq!(
+            build_status("Chatted in {count} conversations", 3),
+            "Chatted in 3 conversations"
+        );
+    }
+
+    #[test]
+    fn build_status_without_placeholder() {
+        assert_eq!(build_status("Online", 3), "Online");
+    }
+
+    #[test]
+    fn display_name_prefers_global_name() {
+        let mut author = serenity::all::User::default();
+        author.name = "hellow_user".to_owned();
+        assert_eq!(display_name(&author, true), Some("hellow_user".to_owned()));
+
+        author.global_name = Some("Hellow".to_owned());
+        assert_eq!(display_name(&author, true), Some("Hellow".to_owned()));
+        assert_eq!(display_name(&author, false), None);
+    }
 }
@@ -0,0 +1,606 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use lib_hive::LifeCycleHandle;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    configuration,
+    external_connections::common::{clean_message, starts_conversation},
+    models::user::{UserAction, UserChannel, UserId},
+};
+
+const SYNC_TIMEOUT_MILLIS: u64 = 30_000;
+const SYNC_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+///Authenticated access to the homeserver's client-server api, shared by the sync loop and replies.
+pub struct MatrixHttp {
+    client: reqwest::Client,
+    homeserver: Url,
+    token: String,
+    next_transaction: AtomicU64,
+}
+
+impl MatrixHttp {
+    pub fn new(homeserver: &str, token: &str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(SYNC_TIMEOUT_MILLIS) + Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            client,
+            homeserver: Url::parse(homeserver)?,
+            token: token.to_owned(),
+            next_transaction: AtomicU64::new(0),
+        })
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> anyhow::Result<Url> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid homeserver url"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+
+    pub async fn send_message(&self, room_id: &str, content: &str) -> anyhow::Result<()> {
+        // Transaction ids only need to be unique per access token.
+        let transaction_id = format!(
+            "{}-{}",
+            Utc::now().timestamp_millis(),
+            self.next_transaction.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = self.endpoint(&["rooms", room_id, "send", "m.room.message", &transaction_id])?;
+        self.client
+            .put(url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "msgtype": "m.text", "body": content }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn whoami(&self) -> anyhow::Result<String> {
+        let url = self.endpoint(&["account", "whoami"])?;
+        let whoami: WhoAmI = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(whoami.user_id)
+    }
+
+    async fn display_name(&self, user_id: &str) -> anyhow::Result<Option<String>> {
+        let url = self.endpoint(&["profile", user_id, "displayname"])?;
+        let profile: Profile = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(profile.displayname)
+    }
+
+    async fn sync(&self, since: Option<&str>) -> anyhow::Result<SyncResponse> {
+        let mut url = self.endpoint(&["sync"])?;
+        url.query_pairs_mut()
+            .append_pair("timeout", &SYNC_TIMEOUT_MILLIS.to_string());
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        let sync = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(sync)
+    }
+
+    async fn joined_member_count(&self, room_id: &str) -> anyhow::Result<u64> {
+        let url = self.endpoint(&["rooms", room_id, "joined_members"])?;
+        let members: JoinedMembers = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(members.joined.len() as u64)
+    }
+
+    async fn join(&self, room_id: &str) -> anyhow::Result<()> {
+        let url = self.endpoint(&["join", room_id])?;
+        self.client
+            .post(url)
+            .bearer_auth(&self.token)
+            .json(&json!({}))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct MatrixClient {
+    http: Arc<MatrixHttp>,
+    user_life_cycle: LifeCycleHandle<UserId, UserAction>,
+}
+
+pub fn prepare_matrix_client(
+    http: Arc<MatrixHttp>,
+    user_life_cycle: LifeCycleHandle<UserId, UserAction>,
+) -> MatrixClient {
+    MatrixClient {
+        http,
+        user_life_cycle,
+    }
+}
+
+///Main Starting point for the Matrix api.
+///The homeserver is only contacted here, so an unreachable server or a bad token doesn't hold up other channels.
+pub async fn run_matrix(client: MatrixClient) -> anyhow::Result<()> {
+    let own_user_id = loop {
+        match client.http.whoami().await {
+            Ok(own_user_id) => break own_user_id,
+            Err(err) => {
+                println!("Matrix login check failed: {err}");
+                tokio::time::sleep(SYNC_RETRY_DELAY).await;
+            }
+        }
+    };
+    // Clients write the display name into the body when mentioning the bot, a missing one just means it isn't stripped.
+    let own_display_name = match client.http.display_name(&own_user_id).await {
+        Ok(display_name) => display_name,
+        Err(err) => {
+            println!("Failed to look up matrix display name: {err}");
+            None
+        }
+    };
+
+    let mut since = tokio::fs::read_to_string(configuration::MATRIX_SYNC_TOKEN_PATH)
+        .await
+        .ok()
+        .map(|token| token.trim().to_owned());
+    let mut member_count_by_room = BTreeMap::<String, u64>::new();
+
+    loop {
+        let sync = match client.http.sync(since.as_deref()).await {
+            Ok(sync) => sync,
+            Err(err) => {
+                println!("Matrix sync failed: {err}");
+                tokio::time::sleep(SYNC_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        for (room_id, room) in &sync.rooms.invite {
+            let inviter = inviter(room, &own_user_id);
+            match accept_invite(inviter, configuration::MATRIX_INVITERS) {
+                true => {
+                    if let Err(err) = client.http.join(room_id).await {
+                        println!("Failed to join matrix room {room_id}: {err}");
+                    }
+                }
+                false => println!(
+                    "Ignored invite to matrix room {room_id} from {}",
+                    inviter.unwrap_or("unknown user")
+                ),
+            }
+        }
+
+        for (room_id, room) in sync.rooms.join {
+            if let Some(count) = room.summary.joined_member_count {
+                member_count_by_room.insert(room_id.clone(), count);
+            }
+
+            // Without a stored token the first sync is the room backlog, which shouldn't be answered.
+            if since.is_none() || room.timeline.events.is_empty() {
+                continue;
+            }
+
+            // Summaries only carry the member count when it changes, rooms not seen since startup are looked up.
+            if !member_count_by_room.contains_key(&room_id) {
+                match client.http.joined_member_count(&room_id).await {
+                    Ok(count) => {
+                        member_count_by_room.insert(room_id.clone(), count);
+                    }
+                    Err(err) => println!("Failed to count members of matrix room {room_id}: {err}"),
+                }
+            }
+            let is_private = member_count_by_room.get(&room_id) == Some(&2);
+
+            for event in room.timeline.events {
+                if let Some((msg, start_conversation)) = filter(
+                    &event,
+                    &own_user_id,
+                    own_display_name.as_deref(),
+                    is_private,
+                    configuration::REQUIRE_WAKE_WORD,
+                ) {
+                    let user_id = user_id(&room_id, &event.sender);
+                    let action = UserAction::NewMessage {
+                        msg,
+                        start_conversation,
+                        display_name: None,
+                    };
+                    if let Err(err) = client.user_life_cycle.act(user_id, action).await {
+                        println!("Failed to dispatch matrix event: {err}");
+                    }
+                }
+            }
+        }
+
+        if let Err(err) =
+            tokio::fs::write(configuration::MATRIX_SYNC_TOKEN_PATH, &sync.next_batch).await
+        {
+            println!("Failed to persist matrix sync token: {err}");
+        }
+        since = Some(sync.next_batch);
+    }
+}
+
+///Filter for Matrix room events.
+/// - only plain text messages from other users are considered.
+/// - if the message mentions the bot, the leading mention text ("Bot: hellow") is removed.
+/// - cleans up the text via `clean_message`, using the bot's user id as its mention.
+/// - starts a conversation if the message mentions the bot or starts with the wake word.
+/// - rooms with just the bot and the user count as private, and behave like Discord DMs.
+fn filter(
+    event: &RoomEvent,
+    own_user_id: &str,
+    own_display_name: Option<&str>,
+    is_private: bool,
+    wake_word: Option<&str>,
+) -> Option<(String, bool)> {
+    if event.event_type != "m.room.message" || event.sender == own_user_id {
+        return None;
+    }
+
+    let content: MessageContent = serde_json::from_value(event.content.clone()).ok()?;
+    if content.msgtype != "m.text" {
+        return None;
+    }
+
+    let mentioned = content
+        .mentions
+        .user_ids
+        .iter()
+        .any(|user_id| user_id == own_user_id);
+    let body = match mentioned {
+        true => strip_mention_prefix(&content.body, own_user_id, own_display_name),
+        false => &content.body,
+    };
+    let (msg, addressed) = clean_message(body, own_user_id, wake_word);
+
+    Some((
+        msg,
+        starts_conversation(addressed || mentioned, is_private, wake_word),
+    ))
+}
+
+///Mentions show up in the body as the pill's text, the bot's display name or localpart, usually followed by ':'.
+fn strip_mention_prefix<'a>(
+    body: &'a str,
+    own_user_id: &str,
+    own_display_name: Option<&str>,
+) -> &'a str {
+    let localpart = own_user_id
+        .trim_start_matches('@')
+        .split(':')
+        .next()
+        .unwrap_or_default();
+    let body = body.trim_start();
+    [Some(own_user_id), own_display_name, Some(localpart)]
+        .into_iter()
+        .flatten()
+        .filter(|name| !name.is_empty())
+        .find_map(|name| {
+            let rest = body
+                .get(..name.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(name))
+                .map(|_| &body[name.len()..])?;
+            match rest.chars().next() {
+                None => Some(rest),
+                Some(':' | ',' | ' ') => Some(rest.trim_start_matches([':', ',']).trim_start()),
+                Some(_) => None,
+            }
+        })
+        .unwrap_or(body)
+}
+
+///Conversations are kept per sender within a room, so one member addressing the bot in a group
+///doesn't open the conversation for everyone else, and admin ids can be matched against senders.
+fn user_id(room_id: &str, sender: &str) -> UserId {
+    UserId(UserChannel::Matrix(room_id.to_owned()), sender.to_owned())
+}
+
+///The user who invited the bot, from the membership event in the invite's stripped state.
+fn inviter<'a>(room: &'a InvitedRoom, own_user_id: &str) -> Option<&'a str> {
+    room.invite_state
+        .events
+        .iter()
+        .find(|event| {
+            event.event_type == "m.room.member" && event.state_key.as_deref() == Some(own_user_id)
+        })
+        .map(|event| event.sender.as_str())
+}
+
+///Invites are accepted from anyone unless an allowlist of inviters is configured.
+fn accept_invite(inviter: Option<&str>, allowlist: Option<&[&str]>) -> bool {
+    match (inviter, allowlist) {
+        (_, None) => true,
+        (Some(inviter), Some(allowlist)) => allowlist.contains(&inviter),
+        (None, Some(_)) => false,
+    }
+}
+
+#[derive(Deserialize)]
+struct Profile {
+    displayname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WhoAmI {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+struct SyncResponse {
+    next_batch: String,
+    #[serde(default)]
+    rooms: Rooms,
+}
+
+#[derive(Deserialize, Default)]
+struct Rooms {
+    #[serde(default)]
+    join: BTreeMap<String, JoinedRoom>,
+    #[serde(default)]
+    invite: BTreeMap<String, InvitedRoom>,
+}
+
+#[derive(Deserialize, Default)]
+struct InvitedRoom {
+    #[serde(default)]
+    invite_state: InviteState,
+}
+
+#[derive(Deserialize, Default)]
+struct InviteState {
+    #[serde(default)]
+    events: Vec<StrippedStateEvent>,
+}
+
+#[derive(Deserialize)]
+struct StrippedStateEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    sender: String,
+    state_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JoinedMembers {
+    #[serde(default)]
+    joined: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    summary: RoomSummary,
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Deserialize, Default)]
+struct RoomSummary {
+    #[serde(rename = "m.joined_member_count")]
+    joined_member_count: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<RoomEvent>,
+}
+
+#[derive(Deserialize)]
+struct RoomEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    sender: String,
+    #[serde(default)]
+    content: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct MessageContent {
+    msgtype: String,
+    body: String,
+    #[serde(rename = "m.mentions", default)]
+    mentions: Mentions,
+}
+
+#[derive(Deserialize, Default)]
+struct Mentions {
+    #[serde(default)]
+    user_ids: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWN_USER_ID: &str = "@bot:example.org";
+
+    fn text_event(sender: &str, body: &str, mentions: &[&str]) -> RoomEvent {
+        RoomEvent {
+            event_type: "m.room.message".to_owned(),
+            sender: sender.to_owned(),
+            content: json!({
+                "msgtype": "m.text",
+                "body": body,
+                "m.mentions": { "user_ids": mentions },
+            }),
+        }
+    }
+
+    #[test]
+    fn filter_skips_own_and_non_text_events() {
+        let own = text_event(OWN_USER_ID, "hellow", &[]);
+        assert_eq!(filter(&own, OWN_USER_ID, None, true, None), None);
+
+        let image = RoomEvent {
+            event_type: "m.room.message".to_owned(),
+            sender: "@user:example.org".to_owned(),
+            content: json!({ "msgtype": "m.image", "body": "cat.png" }),
+        };
+        assert_eq!(filter(&image, OWN_USER_ID, None, true, None), None);
+
+        let topic = RoomEvent {
+            event_type: "m.room.topic".to_owned(),
+            sender: "@user:example.org".to_owned(),
+            content: json!({ "topic": "hellow" }),
+        };
+        assert_eq!(filter(&topic, OWN_USER_ID, None, true, None), None);
+    }
+
+    #[test]
+    fn filter_starts_conversation_in_private_rooms() {
+        let event = text_event("@user:example.org", "Hellow   World", &[]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, true, None),
+            Some(("hellow world".to_owned(), true))
+        );
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, false, None),
+            Some(("hellow world".to_owned(), false))
+        );
+    }
+
+    #[test]
+    fn filter_starts_conversation_on_mention() {
+        let event = text_event("@user:example.org", "Bot: hellow", &[OWN_USER_ID]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, false, None),
+            Some(("hellow".to_owned(), true))
+        );
+    }
+
+    #[test]
+    fn filter_strips_display_name_mention() {
+        let event = text_event(
+            "@user:example.org",
+            "Hellow Bot: how are you",
+            &[OWN_USER_ID],
+        );
+        assert_eq!(
+            filter(&event, OWN_USER_ID, Some("Hellow Bot"), false, None),
+            Some(("how are you".to_owned(), true))
+        );
+
+        let event = text_event("@user:example.org", "@bot:example.org hi", &[OWN_USER_ID]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, Some("Hellow Bot"), false, None),
+            Some(("hi".to_owned(), true))
+        );
+    }
+
+    #[test]
+    fn filter_keeps_names_without_mention() {
+        let event = text_event("@user:example.org", "Bot: hellow", &[]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, Some("Bot"), false, None),
+            Some(("bot: hellow".to_owned(), false))
+        );
+
+        let event = text_event("@user:example.org", "bottle of water", &[OWN_USER_ID]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, false, None),
+            Some(("bottle of water".to_owned(), true))
+        );
+    }
+
+    #[test]
+    fn filter_requires_wake_word_when_configured() {
+        let event = text_event("@user:example.org", "hellow", &[]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, true, Some("bot")),
+            Some(("hellow".to_owned(), false))
+        );
+
+        let event = text_event("@user:example.org", "bot hellow", &[]);
+        assert_eq!(
+            filter(&event, OWN_USER_ID, None, false, Some("bot")),
+            Some(("hellow".to_owned(), true))
+        );
+    }
+
+    #[test]
+    fn group_room_senders_have_separate_conversations() {
+        let room_id = "!room:example.org";
+        let mention = text_event("@alice:example.org", "hellow", &[OWN_USER_ID]);
+        let follow_up = text_event("@bob:example.org", "hellow", &[]);
+
+        assert_eq!(
+            filter(&mention, OWN_USER_ID, None, false, None),
+            Some(("hellow".to_owned(), true))
+        );
+        assert_eq!(
+            filter(&follow_up, OWN_USER_ID, None, false, None),
+            Some(("hellow".to_owned(), false))
+        );
+        assert!(user_id(room_id, &mention.sender) != user_id(room_id, &follow_up.sender));
+        assert_eq!(user_id(room_id, &follow_up.sender).1, "@bob:example.org");
+    }
+
+    #[test]
+    fn inviter_comes_from_own_membership_event() {
+        let room: InvitedRoom = serde_json::from_value(json!({
+            "invite_state": { "events": [
+                { "type": "m.room.name", "sender": "@other:example.org", "state_key": "", "content": {} },
+                { "type": "m.room.member", "sender": "@admin:example.org", "state_key": OWN_USER_ID, "content": { "membership": "invite" } },
+            ] }
+        }))
+        .unwrap();
+
+        assert_eq!(inviter(&room, OWN_USER_ID), Some("@admin:example.org"));
+        assert_eq!(inviter(&InvitedRoom::default(), OWN_USER_ID), None);
+    }
+
+    #[test]
+    fn invites_follow_allowlist() {
+        let allowlist: &[&str] = &["@admin:example.org"];
+
+        assert!(accept_invite(Some("@anyone:example.org"), None));
+        assert!(accept_invite(None, None));
+        assert!(accept_invite(Some("@admin:example.org"), Some(allowlist)));
+        assert!(!accept_invite(Some("@anyone:example.org"), Some(allowlist)));
+        assert!(!accept_invite(None, Some(allowlist)));
+    }
+}
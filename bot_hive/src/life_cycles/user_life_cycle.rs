@@ -1,58 +1,134 @@
-use std::{future::Future, ops::Add, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    ops::Add,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
 
 use crate::{
+    configuration,
     models::user::{User, UserAction, UserChannel, UserId},
     Env,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use lib_hive::{ExternalOperation, Scheduled, TransitionResult};
-use serenity::all::CreateMessage;
+use rand::Rng;
+use serenity::all::{ChannelId, CreateMessage};
 
 type UserTransitionResult = TransitionResult<User, UserAction>;
 type UserExternalOperation = ExternalOperation<UserAction>;
 
+///How long after the last accepted message a follow-up is accepted without addressing the bot.
+const CONVERSATION_WARM_DURATION: Duration = Duration::from_secs(300);
+
+///Largest usable jitter fraction, anything from 1 up could shrink a delay to nothing or below.
+const MAX_JITTER_FRACTION: f64 = 0.99;
+
+///Discord rejects messages longer than this many characters.
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+
 async fn user_transition(
     env: Arc<Env>,
     user_id: UserId,
     user: User,
     action: &UserAction,
+    empty_message_reply: Option<&str>,
 ) -> UserTransitionResult {
+    // Results of external operations started before the latest transition are ignored.
+    if let UserAction::SendResult { epoch, result } = action {
+        if *epoch != user.epoch {
+            match result.as_ref() {
+                Ok(()) => println!("Id: {0} ignored stale result from epoch {epoch}", user_id.1),
+                Err(err) => println!(
+                    "Id: {0} ignored stale result from epoch {epoch}, send failed: {err}",
+                    user_id.1
+                ),
+            }
+            return Ok((user, Vec::new()));
+        }
+    }
+
+    // Messages that don't address the bot are only picked up while a conversation is warm.
+    if let UserAction::NewMessage {
+        start_conversation, ..
+    } = action
+    {
+        if !accept_message(&user, *start_conversation, env.clock.now()) {
+            println!("Id: {0} ignored message outside conversation", user_id.1);
+            return Ok((user, Vec::new()));
+        }
+    }
+
+    // Only transitions that change the user move to a new epoch.
+    let next_epoch = user.epoch + 1;
+
     match action {
         UserAction::Poke => {
             println!("Poked");
             Ok((
                 User {
                     maybe_poke_at: None,
+                    epoch: next_epoch,
                     ..user
                 },
                 Vec::new(),
             ))
         }
         UserAction::NewMessage {
-            msg,
-            start_conversation,
+            msg, display_name, ..
         } => {
-            let mut external = Vec::<UserExternalOperation>::new();
+            let command = admin_command(&configuration::ADMIN_IDS, &user_id, msg);
+            let maintenance = env.maintenance.load(Ordering::Relaxed);
+            if let Some(AdminCommand::Maintenance(maintenance)) = command {
+                env.maintenance.store(maintenance, Ordering::Relaxed);
+            }
 
-            external.push(Box::pin(placeholder_handle_bot_message(
-                env.clone(),
-                user_id.clone(),
-                msg.to_string(),
-            )));
+            let external: Vec<UserExternalOperation> =
+                match reply(command, maintenance, msg, empty_message_reply) {
+                    Reply::Fixed(reply) => vec![Box::pin(send_message(
+                        env.clone(),
+                        user_id.clone(),
+                        reply,
+                        next_epoch,
+                    ))],
+                    Reply::Bot => vec![Box::pin(placeholder_handle_bot_message(
+                        env.clone(),
+                        user_id.clone(),
+                        msg.to_string(),
+                        display_name.clone(),
+                        next_epoch,
+                    ))],
+                    Reply::Silent => {
+                        println!("Id: {0} ignored empty message", user_id.1);
+                        return Ok((user, Vec::new()));
+                    }
+                };
 
             let user = User {
                 action_count: user.action_count + 1,
                 maybe_poke_at: None, //replace with managed time,
+                maybe_last_conversation_at: Some(env.clock.now()),
+                epoch: next_epoch,
             };
 
             println!("Id: {0} {1}", user_id.1, user.action_count);
 
             Ok((user, external))
         }
-        UserAction::SendResult(send_result) => {
+        UserAction::SendResult { result, .. } => {
+            if let Err(err) = result.as_ref() {
+                println!("Id: {0} send failed: {err}", user_id.1);
+            }
             Ok((
                 User {
-                    maybe_poke_at: Some(Utc::now().add(Duration::from_millis(10_000))), //replace with managed time
+                    maybe_poke_at: Some(env.clock.now().add(jittered(
+                        Duration::from_millis(10_000),
+                        configuration::SCHEDULE_JITTER_FRACTION,
+                        &mut rand::thread_rng(),
+                    ))), //replace with managed time
+                    epoch: next_epoch,
                     ..user
                 },
                 Vec::new(),
@@ -67,10 +143,88 @@ pub fn user_transition_wrapper(
     user: User,
     action: &UserAction,
 ) -> Pin<Box<dyn Future<Output = UserTransitionResult> + Send + '_>> {
-    let fut = user_transition(env, user_id, user, action);
+    let fut = user_transition(
+        env,
+        user_id,
+        user,
+        action,
+        configuration::EMPTY_MESSAGE_REPLY,
+    );
     Box::pin(fut)
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum AdminCommand {
+    Maintenance(bool),
+}
+
+///Commands only admins can issue, anyone else's message is treated as a normal message.
+fn admin_command(admin_ids: &[&str], user_id: &UserId, msg: &str) -> Option<AdminCommand> {
+    if !admin_ids.contains(&user_id.1.as_str()) {
+        return None;
+    }
+    match msg {
+        "!maintenance on" => Some(AdminCommand::Maintenance(true)),
+        "!maintenance off" => Some(AdminCommand::Maintenance(false)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    ///A canned reply, sent as is.
+    Fixed(String),
+    ///The message is handed to the bot.
+    Bot,
+    ///The message is ignored.
+    Silent,
+}
+
+///Decides how an accepted message is answered, maintenance mode keeps everything but admin commands from the bot.
+fn reply(
+    command: Option<AdminCommand>,
+    maintenance: bool,
+    msg: &str,
+    empty_message_reply: Option<&str>,
+) -> Reply {
+    let is_empty = msg.trim().is_empty();
+    match (command, maintenance) {
+        (None, _) if is_empty && empty_message_reply.is_none() => Reply::Silent,
+        (Some(AdminCommand::Maintenance(true)), _) => {
+            Reply::Fixed("Maintenance mode on".to_owned())
+        }
+        (Some(AdminCommand::Maintenance(false)), _) => {
+            Reply::Fixed("Maintenance mode off".to_owned())
+        }
+        (None, true) => Reply::Fixed(configuration::MAINTENANCE_MESSAGE.to_owned()),
+        (None, false) => match (is_empty, empty_message_reply) {
+            (true, Some(reply)) => Reply::Fixed(reply.to_owned()),
+            _ => Reply::Bot,
+        },
+    }
+}
+
+///A message is accepted if it addresses the bot, or follows one that did recently.
+fn accept_message(user: &User, start_conversation: bool, now: DateTime<Utc>) -> bool {
+    match (start_conversation, user.maybe_last_conversation_at) {
+        (true, _) => true,
+        (false, Some(last_conversation_at)) => {
+            now < last_conversation_at.add(CONVERSATION_WARM_DURATION)
+        }
+        (false, None) => false,
+    }
+}
+
+///Spreads a delay by up to `fraction` either way, so entities started together don't all fire at once.
+///The fraction is clamped below 1, a negative delay would panic.
+fn jittered(delay: Duration, fraction: f64, rng: &mut impl Rng) -> Duration {
+    let fraction = fraction.clamp(0.0, MAX_JITTER_FRACTION);
+    match fraction > 0.0 {
+        true => delay.mul_f64(1.0 + rng.gen_range(-fraction..=fraction)),
+        false => delay,
+    }
+}
+
 pub fn schedule(user: &User) -> Vec<Scheduled<UserAction>> {
     match user.maybe_poke_at {
         Some(poke_at) => {
@@ -83,44 +237,495 @@ pub fn schedule(user: &User) -> Vec<Scheduled<UserAction>> {
     }
 }
 
+///Wraps a reply in a template like `RESPONSE_TEMPLATE`, e.g. to add a prefix or a disclaimer.
+fn apply_response_template(template: &str, response: &str) -> String {
+    template.replace("{response}", response)
+}
+
+///Cuts a message down to `limit` characters, marking the cut with an ellipsis.
+fn truncate_message(content: String, limit: usize) -> String {
+    match content.chars().count() > limit {
+        true => content
+            .chars()
+            .take(limit.saturating_sub(1))
+            .chain(std::iter::once('…'))
+            .collect(),
+        false => content,
+    }
+}
+
+///Echoes the message back, addressing the user by name when one is given.
+fn placeholder_reply(msg: &str, display_name: Option<&str>) -> String {
+    match display_name {
+        Some(display_name) => format!("{display_name}, you said {msg}"),
+        None => format!("You said {msg}"),
+    }
+}
+
 pub async fn placeholder_handle_bot_message(
     env: Arc<Env>,
     user_id: UserId,
     msg: String,
+    display_name: Option<String>,
+    epoch: u64,
 ) -> UserAction {
-    let user_id_result = match user_id.0 {
-        UserChannel::Discord => {
-            let user_id_result = user_id.1.parse::<u64>();
-            match user_id_result {
-                Ok(user_id) => Ok(serenity::all::UserId::new(user_id)),
-                Err(err) => Err(anyhow::anyhow!(err)),
-            }
+    let reply = apply_response_template(
+        configuration::RESPONSE_TEMPLATE,
+        &placeholder_reply(&msg, display_name.as_deref()),
+    );
+    send_message(env, user_id, reply, epoch).await
+}
+
+///DM channels don't change, so they are created once per user and cached on Env.
+///A failed creation is retried once before giving up.
+async fn dm_channel_id<Create, Created>(
+    dm_channels: &Mutex<BTreeMap<UserId, ChannelId>>,
+    user_id: &UserId,
+    create: Create,
+) -> serenity::Result<ChannelId>
+where
+    Create: Fn() -> Created,
+    Created: Future<Output = serenity::Result<ChannelId>>,
+{
+    let cached = dm_channels.lock().unwrap().get(user_id).copied();
+    match cached {
+        Some(channel_id) => Ok(channel_id),
+        None => {
+            let channel_id = match create().await {
+                Ok(channel_id) => channel_id,
+                Err(_) => create().await?,
+            };
+            dm_channels
+                .lock()
+                .unwrap()
+                .insert(user_id.clone(), channel_id);
+            Ok(channel_id)
         }
-        _ => panic!("Telegram not yet implemented"),
+    }
+}
+
+pub async fn send_message(
+    env: Arc<Env>,
+    user_id: UserId,
+    content: String,
+    epoch: u64,
+) -> UserAction {
+    let result = match &user_id.0 {
+        UserChannel::Discord => send_discord(&env, &user_id, content).await,
+        UserChannel::Matrix(room_id) => send_matrix(&env, room_id, &content).await,
+        UserChannel::Telegram => panic!("Telegram not yet implemented"),
     };
-    match user_id_result {
-        Err(err) => UserAction::SendResult(Arc::new(Err(err))),
-        Ok(user_id) => {
-            let dm_channel_result = match user_id.to_user(&env.discord_http).await {
-                Ok(user) => user.create_dm_channel(&env.discord_http).await,
-                Err(e) => Err(e),
-            };
+    UserAction::SendResult {
+        epoch,
+        result: Arc::new(result),
+    }
+}
 
-            match dm_channel_result {
-                Ok(channel) => {
-                    let res = channel
-                        .send_message(
-                            &env.discord_http,
-                            CreateMessage::new().content(format!("You said {msg}")),
-                        )
-                        .await;
-                    match res {
-                        Ok(_) => UserAction::SendResult(Arc::new(Ok(()))),
-                        Err(err) => UserAction::SendResult(Arc::new(Err(anyhow::anyhow!(err)))),
-                    }
-                }
-                Err(err) => UserAction::SendResult(Arc::new(Err(anyhow::anyhow!(err)))),
+async fn send_discord(env: &Env, user_id: &UserId, content: String) -> anyhow::Result<()> {
+    let discord_user_id = serenity::all::UserId::new(user_id.1.parse::<u64>()?);
+    let channel_id = dm_channel_id(&env.dm_channels, user_id, || async {
+        discord_user_id
+            .create_dm_channel(&env.discord_http)
+            .await
+            .map(|channel| channel.id)
+    })
+    .await?;
+
+    let content = truncate_message(content, DISCORD_MESSAGE_LIMIT);
+    channel_id
+        .send_message(&env.discord_http, CreateMessage::new().content(content))
+        .await?;
+    Ok(())
+}
+
+async fn send_matrix(env: &Env, room_id: &str, content: &str) -> anyhow::Result<()> {
+    match &env.matrix_http {
+        Some(matrix_http) => matrix_http.send_message(room_id, content).await,
+        None => Err(anyhow::anyhow!("Matrix is not configured")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use lib_hive::SystemClock;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serenity::http::HttpBuilder;
+
+    use super::*;
+    use crate::{external_connections::common::starts_conversation, models::bot::BotHandle};
+
+    fn test_env() -> Arc<Env> {
+        Arc::new(Env {
+            discord_http: Arc::new(HttpBuilder::new("FOO").build()),
+            bot_singleton_handle: BotHandle::new(),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            clock: Arc::new(SystemClock),
+            dm_channels: Arc::new(Mutex::new(BTreeMap::new())),
+            matrix_http: None,
+        })
+    }
+
+    fn test_user_id() -> UserId {
+        UserId(UserChannel::Discord, "42".to_owned())
+    }
+
+    #[tokio::test]
+    async fn private_message_without_wake_word_is_ignored() {
+        let action = UserAction::NewMessage {
+            msg: "hellow".to_owned(),
+            start_conversation: starts_conversation(false, true, Some("bot")),
+            display_name: None,
+        };
+
+        let (user, external) =
+            user_transition(test_env(), test_user_id(), User::default(), &action, None)
+                .await
+                .unwrap();
+
+        assert!(external.is_empty());
+        assert_eq!(user.epoch, 0);
+        assert_eq!(user.maybe_last_conversation_at, None);
+    }
+
+    #[tokio::test]
+    async fn private_message_starts_conversation_without_wake_word_configured() {
+        let action = UserAction::NewMessage {
+            msg: "hellow".to_owned(),
+            start_conversation: starts_conversation(false, true, None),
+            display_name: None,
+        };
+
+        let (user, external) =
+            user_transition(test_env(), test_user_id(), User::default(), &action, None)
+                .await
+                .unwrap();
+
+        assert_eq!(external.len(), 1);
+        assert_eq!(user.epoch, 1);
+        assert!(user.maybe_last_conversation_at.is_some());
+    }
+
+    #[test]
+    fn jitter_spreads_across_window() {
+        let delay = Duration::from_secs(10);
+        let mut rng = StdRng::seed_from_u64(7);
+        let delays = (0..100)
+            .map(|_| jittered(delay, 0.1, &mut rng))
+            .collect::<Vec<_>>();
+
+        assert!(delays
+            .iter()
+            .all(|d| *d >= Duration::from_secs(9) && *d <= Duration::from_secs(11)));
+        assert!(delays.iter().any(|d| *d < Duration::from_millis(9_500)));
+        assert!(delays.iter().any(|d| *d > Duration::from_millis(10_500)));
+    }
+
+    #[test]
+    fn jitter_fraction_is_clamped() {
+        let delay = Duration::from_secs(10);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        assert_eq!(jittered(delay, -0.5, &mut rng), delay);
+        assert_eq!(jittered(delay, 0.0, &mut rng), delay);
+        for _ in 0..100 {
+            assert!(jittered(delay, 5.0, &mut rng) > Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn response_template_wraps_reply() {
+        assert_eq!(
+            apply_response_template("Bot: {response} (beta)", "You said hi"),
+            "Bot: You said hi (beta)"
+        );
+        assert_eq!(apply_response_template("{response}", "hi"), "hi");
+    }
+
+    #[test]
+    fn wrapped_reply_is_truncated_to_discord_limit() {
+        let reply = apply_response_template("Bot: {response}", &"a".repeat(DISCORD_MESSAGE_LIMIT));
+        let truncated = truncate_message(reply, DISCORD_MESSAGE_LIMIT);
+
+        assert_eq!(truncated.chars().count(), DISCORD_MESSAGE_LIMIT);
+        assert!(truncated.starts_with("Bot: aaa"));
+        assert!(truncated.ends_with('…'));
+    }
+
+    #[test]
+    fn short_reply_is_not_truncated() {
+        assert_eq!(
+            truncate_message("hellow".to_owned(), DISCORD_MESSAGE_LIMIT),
+            "hellow"
+        );
+        let exact = "é".repeat(DISCORD_MESSAGE_LIMIT);
+        assert_eq!(
+            truncate_message(exact.clone(), DISCORD_MESSAGE_LIMIT),
+            exact
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_send_result_is_ignored() {
+        let user = User {
+            action_count: 2,
+            epoch: 3,
+            ..User::default()
+        };
+        let action = UserAction::SendResult {
+            epoch: 2,
+            result: Arc::new(Err(anyhow::anyhow!("send failed"))),
+        };
+
+        let (user, external) = user_transition(test_env(), test_user_id(), user, &action, None)
+            .await
+            .unwrap();
+
+        assert!(external.is_empty());
+        assert_eq!(user.epoch, 3);
+        assert_eq!(user.action_count, 2);
+        assert_eq!(user.maybe_poke_at, None);
+    }
+
+    #[tokio::test]
+    async fn current_send_result_schedules_poke() {
+        let user = User {
+            epoch: 3,
+            ..User::default()
+        };
+        let action = UserAction::SendResult {
+            epoch: 3,
+            result: Arc::new(Ok(())),
+        };
+
+        let (user, _) = user_transition(test_env(), test_user_id(), user, &action, None)
+            .await
+            .unwrap();
+
+        assert_eq!(user.epoch, 4);
+        assert!(user.maybe_poke_at.is_some());
+    }
+
+    #[test]
+    fn cold_unaddressed_message_is_rejected() {
+        assert!(!accept_message(&User::default(), false, Utc::now()));
+        assert!(accept_message(&User::default(), true, Utc::now()));
+    }
+
+    #[test]
+    fn message_inside_warm_window_is_accepted() {
+        let now = Utc::now();
+        let user = User {
+            maybe_last_conversation_at: Some(now),
+            ..User::default()
+        };
+
+        assert!(accept_message(
+            &user,
+            false,
+            now.add(CONVERSATION_WARM_DURATION - Duration::from_secs(1))
+        ));
+    }
+
+    #[test]
+    fn message_after_warm_window_is_rejected() {
+        let now = Utc::now();
+        let user = User {
+            maybe_last_conversation_at: Some(now),
+            ..User::default()
+        };
+
+        assert!(!accept_message(
+            &user,
+            false,
+            now.add(CONVERSATION_WARM_DURATION)
+        ));
+        assert!(accept_message(
+            &user,
+            true,
+            now.add(CONVERSATION_WARM_DURATION)
+        ));
+    }
+
+    #[test]
+    fn admin_command_only_for_admins() {
+        let admin = UserId(UserChannel::Discord, "1234".to_owned());
+        let other = UserId(UserChannel::Discord, "42".to_owned());
+
+        assert_eq!(
+            admin_command(&["1234"], &admin, "!maintenance on"),
+            Some(AdminCommand::Maintenance(true))
+        );
+        assert_eq!(
+            admin_command(&["1234"], &admin, "!maintenance off"),
+            Some(AdminCommand::Maintenance(false))
+        );
+        assert_eq!(admin_command(&["1234"], &admin, "hellow"), None);
+        assert_eq!(admin_command(&["1234"], &other, "!maintenance on"), None);
+    }
+
+    #[test]
+    fn maintenance_keeps_messages_from_bot() {
+        assert_eq!(
+            reply(None, true, "hellow", None),
+            Reply::Fixed(configuration::MAINTENANCE_MESSAGE.to_owned())
+        );
+        assert_eq!(reply(None, false, "hellow", None), Reply::Bot);
+    }
+
+    #[test]
+    fn admin_commands_answer_during_maintenance() {
+        assert_eq!(
+            reply(
+                Some(AdminCommand::Maintenance(false)),
+                true,
+                "!maintenance off",
+                None
+            ),
+            Reply::Fixed("Maintenance mode off".to_owned())
+        );
+        assert_eq!(
+            reply(
+                Some(AdminCommand::Maintenance(true)),
+                false,
+                "!maintenance on",
+                None
+            ),
+            Reply::Fixed("Maintenance mode on".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn dm_channel_creation_is_retried_and_cached() {
+        let dm_channels = Mutex::new(BTreeMap::new());
+        let attempts = AtomicUsize::new(0);
+        let create = || async {
+            match attempts.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(serenity::Error::Other("first attempt fails")),
+                _ => Ok(ChannelId::new(7)),
             }
+        };
+
+        let channel_id = dm_channel_id(&dm_channels, &test_user_id(), create).await;
+        assert_eq!(channel_id.unwrap(), ChannelId::new(7));
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+
+        let channel_id = dm_channel_id(&dm_channels, &test_user_id(), create).await;
+        assert_eq!(channel_id.unwrap(), ChannelId::new(7));
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn dm_channel_failure_is_not_cached() {
+        let dm_channels = Mutex::new(BTreeMap::new());
+        let create = || async { Err(serenity::Error::Other("always fails")) };
+
+        assert!(dm_channel_id(&dm_channels, &test_user_id(), create)
+            .await
+            .is_err());
+        assert!(dm_channels.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reply_includes_display_name_when_given() {
+        assert_eq!(
+            placeholder_reply("hellow", Some("Hellow")),
+            "Hellow, you said hellow"
+        );
+        assert_eq!(placeholder_reply("hellow", None), "You said hellow");
+    }
+
+    #[test]
+    fn empty_message_reply_is_optional() {
+        assert_eq!(reply(None, false, "   ", None), Reply::Silent);
+        assert_eq!(reply(None, true, "   ", None), Reply::Silent);
+        assert_eq!(
+            reply(None, false, "   ", Some("Did you mean to say something?")),
+            Reply::Fixed("Did you mean to say something?".to_owned())
+        );
+    }
+
+    fn empty_message() -> UserAction {
+        UserAction::NewMessage {
+            msg: "   ".to_owned(),
+            start_conversation: true,
+            display_name: None,
         }
     }
+
+    #[tokio::test]
+    async fn empty_message_is_ignored_without_reply() {
+        let user = User {
+            epoch: 3,
+            ..User::default()
+        };
+
+        let (user, external) =
+            user_transition(test_env(), test_user_id(), user, &empty_message(), None)
+                .await
+                .unwrap();
+
+        assert!(external.is_empty());
+        assert_eq!(user.epoch, 3);
+        assert_eq!(user.action_count, 0);
+        assert_eq!(user.maybe_last_conversation_at, None);
+    }
+
+    #[tokio::test]
+    async fn empty_message_gets_configured_reply() {
+        let user = User {
+            epoch: 3,
+            ..User::default()
+        };
+
+        let (user, external) = user_transition(
+            test_env(),
+            test_user_id(),
+            user,
+            &empty_message(),
+            Some("Did you mean to say something?"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(external.len(), 1);
+        assert_eq!(user.epoch, 4);
+        assert!(user.maybe_last_conversation_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn group_room_follow_up_from_another_sender_is_not_accepted() {
+        let room = UserChannel::Matrix("!room:example.org".to_owned());
+        let alice = UserId(room.clone(), "@alice:example.org".to_owned());
+        let bob = UserId(room, "@bob:example.org".to_owned());
+        let mention = UserAction::NewMessage {
+            msg: "hellow".to_owned(),
+            start_conversation: true,
+            display_name: None,
+        };
+        let follow_up = UserAction::NewMessage {
+            msg: "hellow again".to_owned(),
+            start_conversation: false,
+            display_name: None,
+        };
+
+        let (alice_state, _) =
+            user_transition(test_env(), alice.clone(), User::default(), &mention, None)
+                .await
+                .unwrap();
+
+        // Each sender is its own entity, so bob starts out cold.
+        let (bob_state, external) =
+            user_transition(test_env(), bob, User::default(), &follow_up, None)
+                .await
+                .unwrap();
+        assert!(external.is_empty());
+        assert_eq!(bob_state.maybe_last_conversation_at, None);
+
+        let (_, external) = user_transition(test_env(), alice, alice_state, &follow_up, None)
+            .await
+            .unwrap();
+        assert_eq!(external.len(), 1);
+    }
 }
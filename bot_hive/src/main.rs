@@ -6,10 +6,15 @@ mod life_cycles;
 mod models;
 
 use external_connections::discord::*;
-use lib_hive::{new_life_cycle, Schedule, Transition};
+use external_connections::matrix::*;
+use lib_hive::{new_life_cycle, Clock, Schedule, SystemClock, Transition};
 use models::bot::{BotAction, BotHandle};
-use serenity::all::{Http, HttpBuilder};
-use std::sync::Arc;
+use models::user::UserId;
+use serenity::all::{ChannelId, Http, HttpBuilder};
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
 use tokio::task::JoinSet;
 
 use crate::life_cycles::user_life_cycle::{schedule, user_transition_wrapper};
@@ -18,6 +23,10 @@ use crate::life_cycles::user_life_cycle::{schedule, user_transition_wrapper};
 struct Env {
     discord_http: Arc<Http>,
     bot_singleton_handle: BotHandle,
+    maintenance: Arc<AtomicBool>,
+    clock: Arc<dyn Clock>,
+    dm_channels: Arc<Mutex<BTreeMap<UserId, ChannelId>>>,
+    matrix_http: Option<Arc<MatrixHttp>>,
 }
 
 #[tokio::main]
@@ -31,13 +40,35 @@ async fn main() -> anyhow::Result<!> {
 
     let discord_http =
         Arc::new(HttpBuilder::new(configuration::client_tokens::discord_token).build());
+    let matrix_http =
+        configuration::MATRIX_HOMESERVER.and_then(|homeserver| {
+            match MatrixHttp::new(homeserver, configuration::client_tokens::matrix_token) {
+                Ok(matrix_http) => Some(Arc::new(matrix_http)),
+                Err(err) => {
+                    println!("Matrix disabled, invalid configuration: {err}");
+                    None
+                }
+            }
+        });
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
     let env = Arc::new(Env {
         discord_http,
         bot_singleton_handle,
+        maintenance: Arc::new(AtomicBool::new(false)),
+        clock: clock.clone(),
+        dm_channels: Arc::new(Mutex::new(BTreeMap::new())),
+        matrix_http: matrix_http.clone(),
     });
 
-    let user_life_cycle =
-        new_life_cycle(env, Transition(user_transition_wrapper), Schedule(schedule));
+    let user_life_cycle = new_life_cycle(
+        env,
+        Transition(user_transition_wrapper),
+        Schedule(schedule),
+        clock,
+    );
+
+    let maybe_matrix_client =
+        matrix_http.map(|matrix_http| prepare_matrix_client(matrix_http, user_life_cycle.clone()));
 
     let discord_client =
         prepare_discord_client(configuration::client_tokens::discord_token, user_life_cycle)
@@ -45,6 +76,10 @@ async fn main() -> anyhow::Result<!> {
 
     let mut set = JoinSet::new();
 
+    if let Some(matrix_client) = maybe_matrix_client {
+        set.spawn(run_matrix(matrix_client));
+    }
+
     let clients = vec![run_discord(discord_client)];
 
     clients.into_iter().for_each(|client| {
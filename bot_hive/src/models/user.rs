@@ -7,12 +7,15 @@ use tokio::sync::mpsc;
 pub enum UserChannel {
     Telegram,
     Discord,
+    ///The Matrix room replies go to, users are told apart by sender within it.
+    Matrix(String),
 }
 impl UserChannel {
     fn to_string(&self) -> &'static str {
         match self {
             UserChannel::Telegram => "Telegram",
             UserChannel::Discord => "Discord",
+            UserChannel::Matrix(_) => "Matrix",
         }
     }
 }
@@ -24,6 +27,10 @@ pub struct UserId(pub UserChannel, pub String);
 pub struct User {
     pub action_count: usize,
     pub maybe_poke_at: Option<DateTime<Utc>>,
+    /// When the bot last accepted a message from the user, including warm follow-ups, so the window extends with each one.
+    pub maybe_last_conversation_at: Option<DateTime<Utc>>,
+    /// Bumped only on transitions that change the user, external results tagged with an older epoch are stale.
+    pub epoch: u64,
 }
 
 #[derive(Clone)]
@@ -31,7 +38,11 @@ pub enum UserAction {
     NewMessage {
         msg: String,
         start_conversation: bool,
+        display_name: Option<String>,
     },
     Poke,
-    SendResult(Arc<anyhow::Result<()>>),
+    SendResult {
+        epoch: u64,
+        result: Arc<anyhow::Result<()>>,
+    },
 }
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use tokio::sync::mpsc;
 
-use crate::{run_entity, Activity, LifeCycleHandle, LifeCycleItem, Schedule, Transition};
+use crate::{run_entity, Activity, Clock, LifeCycleHandle, LifeCycleItem, Schedule, Transition};
 
 #[derive(Clone)]
 pub struct Handle<Action>
@@ -16,12 +16,11 @@ impl<Action> Handle<Action>
 where
     Action: LifeCycleItem + 'static,
 {
-    pub async fn act(&self, action: Action) {
-        let _ = self
-            .sender
+    pub async fn act(&self, action: Action) -> anyhow::Result<()> {
+        self.sender
             .send(Activity::LifeCycleAction(action))
             .await
-            .expect("Send failed");
+            .map_err(|_| anyhow::anyhow!("Entity channel closed"))
     }
 }
 
@@ -36,6 +35,7 @@ pub fn new_entity<
     user_life_cycle_handle: LifeCycleHandle<Id, Action>,
     transition: Transition<Id, State, Action, Env>,
     schedule: Schedule<State, Action>,
+    clock: Arc<dyn Clock>,
 ) -> Handle<Action> {
     let (sender, receiver) = mpsc::channel(8);
     tokio::spawn(run_entity(
@@ -45,6 +45,7 @@ pub fn new_entity<
         user_life_cycle_handle,
         transition,
         schedule,
+        clock,
         sender.clone(),
     ));
     Handle { sender }
@@ -1,10 +1,13 @@
 #![feature(const_option)]
 mod bee_handle;
+mod clock;
 mod life_cycle_handle;
 
 use bee_handle::{new_entity, Handle};
 use chrono::{DateTime, TimeDelta, Utc};
+pub use clock::*;
 pub use life_cycle_handle::*;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 use std::{future::Future, pin::Pin, sync::Arc};
 
@@ -47,6 +50,7 @@ pub enum Activity<Action: LifeCycleItem + 'static> {
     DeleteSelf,
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_entity<
     Id: LifeCycleItem + Ord + 'static,
     State: LifeCycleItem + Default + 'static,
@@ -59,13 +63,14 @@ async fn run_entity<
     handle: LifeCycleHandle<Id, Action>,
     transition: Transition<Id, State, Action, Env>,
     schedule: Schedule<State, Action>,
+    clock: Arc<dyn Clock>,
     self_sender: Sender<Activity<Action>>,
 ) {
     let mut state = State::default();
     let mut maybe_scheduled: Option<JoinHandle<()>> = None;
 
     while let Some(activity) = receiver.recv().await {
-        let now = Utc::now();
+        let now = clock.now();
 
         println!("TRANSITION AT {now}");
         match activity {
@@ -87,6 +92,7 @@ async fn run_entity<
                         match earliest {
                             Some(scheduled) => {
                                 let self_sender = self_sender.clone();
+                                let clock = clock.clone();
                                 let timer_handle = tokio::spawn(async move {
                                     let sleep_for = scheduled.clone().at - now;
                                     match sleep_for <= ZERO_TIME_DELTA {
@@ -94,8 +100,7 @@ async fn run_entity<
                                         false => {
                                             let sleep_for = sleep_for.to_std().unwrap();
 
-                                            tokio::time::sleep(sleep_for + Duration::from_secs(2))
-                                                .await;
+                                            clock.sleep(sleep_for + Duration::from_secs(2)).await;
 
                                             let _ = self_sender
                                                 .clone()
@@ -115,7 +120,9 @@ async fn run_entity<
                             let user_id = id.clone();
                             tokio::spawn(async move {
                                 let action = f.await;
-                                handle.act(user_id, action).await;
+                                if let Err(err) = handle.act(user_id, action).await {
+                                    println!("DEAD LETTER: external result dropped, {err}");
+                                }
                             });
                         });
                         state = updated_user;
@@ -163,25 +170,182 @@ async fn start_life_cycle<
     mut receiver: Receiver<(Id, Action)>,
     transition: Transition<Id, State, Action, Env>,
     schedule: Schedule<State, Action>,
+    clock: Arc<dyn Clock>,
 ) -> ! {
     let mut handle_by_id = std::collections::BTreeMap::<Id, Handle<Action>>::new();
 
     while let Some((id, action)) = receiver.recv().await {
-        match handle_by_id.contains_key(&id) {
+        // An entity whose task has ended is recreated so the action isn't lost.
+        let is_alive = handle_by_id
+            .get(&id)
+            .is_some_and(|handle| !handle.sender.is_closed());
+        match is_alive {
             true => (),
             false => {
+                if handle_by_id.contains_key(&id) {
+                    println!("Entity channel closed, recreating");
+                }
                 let handle = new_entity(
                     env.clone(),
                     id.clone(),
                     life_cycle_handle.clone(),
                     transition.clone(),
                     schedule.clone(),
+                    clock.clone(),
                 );
                 handle_by_id.insert(id.clone(), handle.clone());
+                life_cycle_handle
+                    .entity_count
+                    .store(handle_by_id.len(), Ordering::Relaxed);
             }
         }
         let handle = handle_by_id[&id].clone();
-        tokio::spawn(async move { handle.act(action).await });
+        tokio::spawn(async move {
+            if let Err(err) = handle.act(action).await {
+                println!("DEAD LETTER: action dropped, {err}");
+            }
+        });
     }
     panic!()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[derive(Clone)]
+    struct TestEnv {
+        events: Sender<String>,
+    }
+
+    #[derive(Clone)]
+    enum TestAction {
+        Record(String),
+        Crash,
+    }
+
+    fn test_transition(
+        env: Arc<TestEnv>,
+        _id: u64,
+        state: u64,
+        action: &TestAction,
+    ) -> Pin<Box<dyn Future<Output = TransitionResult<u64, TestAction>> + Send + '_>> {
+        Box::pin(async move {
+            match action {
+                TestAction::Record(msg) => {
+                    env.events.send(msg.clone()).await?;
+                    Ok((state + 1, Vec::new()))
+                }
+                TestAction::Crash => {
+                    env.events.send("crashing".to_owned()).await?;
+                    panic!("entity crashed")
+                }
+            }
+        })
+    }
+
+    fn no_schedule(_state: &u64) -> Vec<Scheduled<TestAction>> {
+        Vec::new()
+    }
+
+    type PokeState = Option<DateTime<Utc>>;
+
+    #[derive(Clone)]
+    struct PokeEnv {
+        events: Sender<DateTime<Utc>>,
+        clock: Arc<TokioClock>,
+    }
+
+    #[derive(Clone)]
+    enum PokeAction {
+        Start,
+        Poke,
+    }
+
+    fn poke_transition(
+        env: Arc<PokeEnv>,
+        _id: u64,
+        _state: PokeState,
+        action: &PokeAction,
+    ) -> Pin<Box<dyn Future<Output = TransitionResult<PokeState, PokeAction>> + Send + '_>> {
+        Box::pin(async move {
+            match action {
+                PokeAction::Start => Ok((Some(env.clock.now() + POKE_DELAY), Vec::new())),
+                PokeAction::Poke => {
+                    env.events.send(env.clock.now()).await?;
+                    Ok((None, Vec::new()))
+                }
+            }
+        })
+    }
+
+    fn poke_schedule(state: &PokeState) -> Vec<Scheduled<PokeAction>> {
+        state
+            .iter()
+            .map(|at| Scheduled {
+                at: *at,
+                action: PokeAction::Poke,
+            })
+            .collect()
+    }
+
+    const POKE_DELAY: Duration = Duration::from_secs(10);
+
+    #[tokio::test]
+    async fn closed_entity_is_recreated() {
+        let (events, mut received) = mpsc::channel(8);
+        let handle = new_life_cycle(
+            Arc::new(TestEnv { events }),
+            Transition(test_transition),
+            Schedule(no_schedule),
+            Arc::new(SystemClock),
+        );
+
+        handle.act(1, TestAction::Crash).await.unwrap();
+        assert_eq!(received.recv().await.unwrap(), "crashing");
+        // Until the crashed task has unwound, its channel still accepts actions and drops them,
+        // so keep acting until one reaches the recreated entity.
+        let msg = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                handle
+                    .act(1, TestAction::Record("after crash".to_owned()))
+                    .await
+                    .unwrap();
+                if let Ok(Some(msg)) =
+                    tokio::time::timeout(Duration::from_millis(10), received.recv()).await
+                {
+                    break msg;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(msg, "after crash");
+        assert_eq!(handle.entity_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_poke_fires_on_tokio_clock() {
+        let start = Utc::now();
+        let clock = Arc::new(TokioClock::new(start));
+        let (events, mut received) = mpsc::channel(8);
+        let handle = new_life_cycle(
+            Arc::new(PokeEnv {
+                events,
+                clock: clock.clone(),
+            }),
+            Transition(poke_transition),
+            Schedule(poke_schedule),
+            clock,
+        );
+
+        handle.act(1, PokeAction::Start).await.unwrap();
+        tokio::time::advance(POKE_DELAY / 2).await;
+        assert!(received.try_recv().is_err());
+
+        tokio::time::advance(POKE_DELAY).await;
+        let poked_at = received.recv().await.unwrap();
+        assert!(poked_at >= start + POKE_DELAY);
+    }
+}
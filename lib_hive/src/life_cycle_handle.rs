@@ -1,8 +1,11 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 use tokio::sync::mpsc;
 
-use crate::{start_life_cycle, LifeCycleItem, Schedule, Transition};
+use crate::{start_life_cycle, Clock, LifeCycleItem, Schedule, Transition};
 
 #[derive(Clone)]
 pub struct LifeCycleHandle<Id, Action>
@@ -11,6 +14,7 @@ where
     Action: LifeCycleItem,
 {
     pub sender: mpsc::Sender<(Id, Action)>,
+    pub entity_count: Arc<AtomicUsize>,
 }
 
 impl<Id, Action> LifeCycleHandle<Id, Action>
@@ -18,12 +22,16 @@ where
     Id: LifeCycleItem + Ord + 'static,
     Action: LifeCycleItem + 'static,
 {
-    pub async fn act(&self, user_id: Id, user_action: Action) {
-        let _ = self
-            .sender
+    pub async fn act(&self, user_id: Id, user_action: Action) -> anyhow::Result<()> {
+        self.sender
             .send((user_id, user_action))
             .await
-            .expect("Send failed");
+            .map_err(|_| anyhow::anyhow!("Life cycle channel closed"))
+    }
+
+    ///Number of entities created since startup, entities are never dropped so this only grows.
+    pub fn entity_count(&self) -> usize {
+        self.entity_count.load(Ordering::Relaxed)
     }
 }
 
@@ -36,15 +44,20 @@ pub fn new_life_cycle<
     env: Arc<Env>,
     transition: Transition<Id, State, Action, Env>,
     schedule: Schedule<State, Action>,
+    clock: Arc<dyn Clock>,
 ) -> LifeCycleHandle<Id, Action> {
     let (sender, receiver) = mpsc::channel(8);
-    let user_life_cycle_handle = LifeCycleHandle { sender };
+    let user_life_cycle_handle = LifeCycleHandle {
+        sender,
+        entity_count: Arc::new(AtomicUsize::new(0)),
+    };
     tokio::spawn(start_life_cycle(
         env,
         user_life_cycle_handle.clone(),
         receiver,
         transition,
         schedule,
+        clock,
     ));
     user_life_cycle_handle
 }